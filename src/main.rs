@@ -2,16 +2,193 @@
 
 use eframe::egui;
 use gst::prelude::*;
+use gst_webrtc::prelude::*;
+use serde::{Deserialize, Serialize};
+use std::collections::{HashMap, HashSet, VecDeque};
 use std::sync::{Arc, Mutex};
+use std::time::{Duration, Instant};
 use anyhow::Error;
 
+/// リモートのproducerを一意に識別するID(webrtcsrcのsignallerが払い出すもの)
+type ProducerId = String;
+
+/// get-statsのポーリング間隔
+const STATS_POLL_INTERVAL: Duration = Duration::from_secs(1);
+/// ビットレートのスパークラインに残す履歴の長さ(秒)
+const STATS_HISTORY_LEN: usize = 30;
+
+/// 1producer分の回線品質(gst-meetのColibri品質レポートに倣った指標)
+#[derive(Debug, Clone, Default)]
+struct Stats {
+    bitrate_bps: f64,
+    packet_loss_pct: f64,
+    rtt_ms: f64,
+    jitter_ms: f64,
+    /// 直近`STATS_HISTORY_LEN`秒分の受信ビットレート推移
+    bitrate_history: VecDeque<f64>,
+}
+
+/// bytes-received等の差分計算に使う、前回ポーリング時点のinbound-rtpスナップショット
+#[derive(Debug, Clone, Copy, Default)]
+struct InboundRtpSnapshot {
+    bytes_received: u64,
+    at: Option<Instant>,
+}
+
+/// `eframe::Storage`に永続化するキー
+const SETTINGS_STORAGE_KEY: &str = "webrtc_egui_settings";
+
+/// producerごとのデコードブランチに刺した録画用分岐点(`tee`)
+#[derive(Debug, Clone, Default)]
+struct ProducerTees {
+    video: Option<gst::Element>,
+    audio: Option<gst::Element>,
+}
+
+/// 録画中の1producer分の要素。停止時の後片付けに使う
+struct RecordingBranch {
+    video_elements: Vec<gst::Element>,
+    audio_elements: Vec<gst::Element>,
+    video_tee_pad: Option<gst::Pad>,
+    audio_tee_pad: Option<gst::Pad>,
+    muxer: gst::Element,
+    filesink: gst::Element,
+}
+
+/// webrtcsrcが差し替え可能なsignallerバックエンド。
+/// gst-plugins-rs側のwebrtcsinkがAwsKvsSignaller/LiveKitSignaller/
+/// JanusVRSignaller/WhipClientSignallerを切り替えるのと対になる受信側の選択肢。
+/// このアプリはwebrtcsrc(受信側)なので、WHIPの送信側シグナラーではなく
+/// WHEPの受信側シグナラーを使う。
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+enum SignallingBackend {
+    /// デフォルトのwebsocketシグナラー(webrtcsrc組み込み)
+    Default,
+    LiveKit,
+    Janus,
+    Whep,
+}
+
+impl SignallingBackend {
+    const ALL: [SignallingBackend; 4] = [
+        SignallingBackend::Default,
+        SignallingBackend::LiveKit,
+        SignallingBackend::Janus,
+        SignallingBackend::Whep,
+    ];
+
+    fn label(&self) -> &'static str {
+        match self {
+            SignallingBackend::Default => "Default (websocket)",
+            SignallingBackend::LiveKit => "LiveKit",
+            SignallingBackend::Janus => "Janus VideoRoom",
+            SignallingBackend::Whep => "WHEP",
+        }
+    }
+
+    /// signallerプロパティに差し込むGObjectの型名
+    fn gobject_type_name(&self) -> &'static str {
+        match self {
+            SignallingBackend::Default => "GstWebRTCSignaller",
+            SignallingBackend::LiveKit => "GstLiveKitSignaller",
+            SignallingBackend::Janus => "GstJanusVRSignaller",
+            // webrtcsinkが使う送信側のGstWhipClientSignallerとは別物で、
+            // webrtcsrc(受信側)用のWHEPクライアントシグナラーを使う
+            SignallingBackend::Whep => "GstWhepClientSignaller",
+        }
+    }
+}
+
+/// パイプラインを駆動するクロックの選択肢。RFC 7273でのA/V・複数ストリーム
+/// 同期精度を上げるためにネットワーククロックへ切り替えられるようにする。
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+enum ClockMode {
+    /// パイプライン既定のシステムクロックをそのまま使う
+    PipelineDefault,
+    Ntp,
+    Ptp,
+}
+
+impl ClockMode {
+    const ALL: [ClockMode; 3] = [ClockMode::PipelineDefault, ClockMode::Ntp, ClockMode::Ptp];
+
+    fn label(&self) -> &'static str {
+        match self {
+            ClockMode::PipelineDefault => "パイプライン既定",
+            ClockMode::Ntp => "NTP (GstNtpClock)",
+            ClockMode::Ptp => "PTP (GstPtpClock)",
+        }
+    }
+}
+
+/// 次回起動時にも引き継ぐ接続設定
+#[derive(Debug, Clone, Serialize, Deserialize)]
+struct Settings {
+    backend: SignallingBackend,
+    server_uri: String,
+    stun_server: String,
+    turn_server: String,
+    room_id: String,
+    producer_peer_id: String,
+    clock_mode: ClockMode,
+    ntp_host: String,
+    ptp_domain: u32,
+    clock_sync_timeout_secs: u32,
+    /// 録画ファイルの出力先ディレクトリ
+    recording_dir: String,
+}
+
+impl Default for Settings {
+    fn default() -> Self {
+        Self {
+            backend: SignallingBackend::Default,
+            server_uri: String::new(),
+            stun_server: "stun://stun.l.google.com:19302".to_string(),
+            turn_server: String::new(),
+            room_id: String::new(),
+            producer_peer_id: String::new(),
+            clock_mode: ClockMode::PipelineDefault,
+            ntp_host: "pool.ntp.org".to_string(),
+            ptp_domain: 0,
+            clock_sync_timeout_secs: 5,
+            recording_dir: ".".to_string(),
+        }
+    }
+}
+
 /// WebRTC受信アプリケーションの状態を管理する構造体
 struct WebRtcApp {
     pipeline: Option<gst::Pipeline>,
     logs: Arc<Mutex<Vec<String>>>,
     is_running: bool,
-    video_texture: Option<egui::TextureHandle>,
-    video_frame: Arc<Mutex<Option<VideoFrame>>>,
+    video_textures: HashMap<ProducerId, egui::TextureHandle>,
+    video_frames: Arc<Mutex<HashMap<ProducerId, VideoFrame>>>,
+    /// producerごとに追加したデコードブランチの要素(producer-removed時の後片付け用)
+    producer_branches: Arc<Mutex<HashMap<ProducerId, Vec<gst::Element>>>>,
+    /// session_id -> control用データチャンネル(NavigationEventの送信に使う)
+    control_channels: Arc<Mutex<HashMap<String, gst_webrtc::WebRTCDataChannel>>>,
+    /// producer_id -> session_id(送信先チャンネルを引くためのキー変換)
+    producer_sessions: Arc<Mutex<HashMap<ProducerId, String>>>,
+    /// session_id -> producer_id(stats取得時の逆引き)
+    session_producers: Arc<Mutex<HashMap<String, ProducerId>>>,
+    /// session_id -> webrtcbin(統計ポーリングスレッドがget-statsを呼ぶために保持)
+    webrtcbins: Arc<Mutex<HashMap<String, gst::Element>>>,
+    /// producer_id -> 直近の回線品質
+    stats: Arc<Mutex<HashMap<ProducerId, Stats>>>,
+    /// producer_id -> デコード済みストリームの分岐点(録画開始時にここから分岐する)
+    producer_tees: Arc<Mutex<HashMap<ProducerId, ProducerTees>>>,
+    /// 録画中のproducer_id一覧とその後片付け用ハンドル
+    recordings: Arc<Mutex<HashMap<ProducerId, RecordingBranch>>>,
+    /// 直近でポインタがホバーしていたタイル。キー入力の送り先に使う
+    focused_producer: Option<ProducerId>,
+    /// 直近でMouseMoveとして送信したフレーム座標。ホバー中は毎フレーム(約60fps)
+    /// repaintされるため、座標が変化していない限り再送しないようにするためのキャッシュ
+    last_sent_pointer_pos: HashMap<ProducerId, (f64, f64)>,
+    /// producer-addedで通知されたproducer_idの一覧。pad名から取り出したIDが本当に
+    /// producer idと一致しているかをpad-added側で検証するために保持する
+    known_producers: Arc<Mutex<HashSet<ProducerId>>>,
+    /// シグナリングバックエンドや接続先の設定(再起動後も保持)
+    settings: Settings,
 }
 
 /// ビデオフレームデータを保持する構造体
@@ -35,13 +212,38 @@ impl Default for WebRtcApp {
             pipeline: None,
             logs: Arc::new(Mutex::new(Vec::new())),
             is_running: false,
-            video_texture: None,
-            video_frame: Arc::new(Mutex::new(None)),
+            video_textures: HashMap::new(),
+            video_frames: Arc::new(Mutex::new(HashMap::new())),
+            producer_branches: Arc::new(Mutex::new(HashMap::new())),
+            control_channels: Arc::new(Mutex::new(HashMap::new())),
+            producer_sessions: Arc::new(Mutex::new(HashMap::new())),
+            session_producers: Arc::new(Mutex::new(HashMap::new())),
+            webrtcbins: Arc::new(Mutex::new(HashMap::new())),
+            stats: Arc::new(Mutex::new(HashMap::new())),
+            producer_tees: Arc::new(Mutex::new(HashMap::new())),
+            recordings: Arc::new(Mutex::new(HashMap::new())),
+            focused_producer: None,
+            last_sent_pointer_pos: HashMap::new(),
+            known_producers: Arc::new(Mutex::new(HashSet::new())),
+            settings: Settings::default(),
         }
     }
 }
 
 impl WebRtcApp {
+    /// 前回終了時の設定(あれば)を読み込んで初期化する
+    fn new(cc: &eframe::CreationContext<'_>) -> Self {
+        let settings = cc
+            .storage
+            .and_then(|storage| eframe::get_value(storage, SETTINGS_STORAGE_KEY))
+            .unwrap_or_default();
+
+        Self {
+            settings,
+            ..Self::default()
+        }
+    }
+
     fn add_log(&self, message: String) {
         if let Ok(mut logs) = self.logs.lock() {
             logs.push(message);
@@ -52,6 +254,30 @@ impl WebRtcApp {
         }
     }
 
+    /// producerへNavigationEvent(JSON文字列)をcontrolデータチャンネル経由で送る
+    fn send_navigation_event(&self, producer_id: &str, event_json: String) {
+        let Ok(producer_sessions) = self.producer_sessions.lock() else { return };
+        let Some(session_id) = producer_sessions.get(producer_id) else { return };
+        let Ok(control_channels) = self.control_channels.lock() else { return };
+        if let Some(channel) = control_channels.get(session_id) {
+            channel.send_string(Some(&event_json));
+        }
+    }
+
+    /// `self.settings`に従ってsignaller GObjectを構築する。STUN/TURNは
+    /// signallerではなくwebrtcsrc自身のプロパティなのでここでは扱わない。
+    fn build_signaller(&self) -> Result<gst::glib::Object, Error> {
+        let type_name = self.settings.backend.gobject_type_name();
+        let signaller_type = gst::glib::Type::from_name(type_name).ok_or_else(|| {
+            anyhow::anyhow!(
+                "signaller type `{}` が見つかりません(対応するgst-plugins-rsプラグインが未インストールの可能性があります)",
+                type_name
+            )
+        })?;
+
+        Ok(gst::glib::Object::with_type(signaller_type))
+    }
+
     fn start_pipeline(&mut self) -> Result<(), Error> {
         if self.is_running {
             return Ok(());
@@ -60,28 +286,154 @@ impl WebRtcApp {
         let pipeline = gst::Pipeline::builder().build();
 
         // webrtcsrcの作成 - 低遅延設定
+        // connect-to-first-producerは使わず、producer-added/producer-removedを
+        // 購読して全producer分のデコードブランチを動的に張る(複数参加者対応)。
         let webrtcsrc = gst::ElementFactory::make("webrtcsrc")
-            .property("connect-to-first-producer", true)
             .property_from_str("video-codecs", "<H264, VP8>")
             .property_from_str("audio-codecs", "<OPUS>")
             .property("enable-control-data-channel", true)
             .build()?;
 
+        // ネットワーククロックを使う場合はRFC 7273のクロック/RTPオフセットのシグナリングも
+        // 有効にする。プロパティ名はインストールされているプラグインのバージョンに依存するため、
+        // 常にbuilderへ積んでbuild()自体を失敗させるのは避け、存在確認の上でset_propertyする。
+        if self.settings.clock_mode != ClockMode::PipelineDefault {
+            if webrtcsrc.has_property("rfc7273-sync", None) {
+                webrtcsrc.set_property("rfc7273-sync", true);
+            } else {
+                self.add_log(
+                    "⚠️ rfc7273-syncプロパティが見つからないため、RTPクロックオフセットのシグナリングは有効化されません"
+                        .to_string(),
+                );
+            }
+        }
+
+        // STUN/TURNはsignallerではなくwebrtcsrc自身(実体はwebrtcbinへ転送される)の
+        // プロパティなので、signallerの差し替えより前にwebrtcsrcへ直接設定する
+        for (name, value) in [
+            ("stun-server", &self.settings.stun_server),
+            ("turn-server", &self.settings.turn_server),
+        ] {
+            if !value.is_empty() && webrtcsrc.has_property(name, None) {
+                webrtcsrc.set_property(name, value);
+            }
+        }
+
         pipeline.add(&webrtcsrc)?;
 
+        // 選択されたバックエンドのsignallerに差し替える(Defaultは組み込みのものをそのまま使う)
+        if self.settings.backend != SignallingBackend::Default {
+            let signaller = self.build_signaller()?;
+            webrtcsrc.set_property("signaller", &signaller);
+        }
+
         let signaller = webrtcsrc.property::<gst::glib::Object>("signaller");
 
+        // server_uri/room_id/producer_peer_idはDefaultバックエンド(webrtcsrc組み込みの
+        // signaller)でも設定できないと「サーバーURIを設定可能」という機能が死んでしまうため、
+        // バックエンドを問わず、差し替え後に実際に使われるsignallerオブジェクトへ適用する
+        apply_signaller_string_props(&signaller, &self.settings);
+
         // ログ用のクロージャ
         let logs = self.logs.clone();
+        let known_producers_for_added = self.known_producers.clone();
         signaller.connect("producer-added", false, move |args| {
             let producer_id = args[1].get::<String>().unwrap();
             let meta = args[2].get::<Option<gst::Structure>>().unwrap();
+            if let Ok(mut known_producers) = known_producers_for_added.lock() {
+                known_producers.insert(producer_id.clone());
+            }
             if let Ok(mut logs) = logs.lock() {
                 logs.push(format!("🎤 Producer追加: producer_id={}, meta={:?}", producer_id, meta));
             }
             None
         });
 
+        // producer-removed: タイルのテクスチャ/フレームとデコードブランチを片付ける
+        let video_frames_for_removed = self.video_frames.clone();
+        let producer_branches_for_removed = self.producer_branches.clone();
+        let logs_for_removed = self.logs.clone();
+        let pipeline_weak_for_removed = pipeline.downgrade();
+        let stats_for_removed = self.stats.clone();
+        let producer_sessions_for_removed = self.producer_sessions.clone();
+        let session_producers_for_removed = self.session_producers.clone();
+        let webrtcbins_for_removed = self.webrtcbins.clone();
+        let producer_tees_for_removed = self.producer_tees.clone();
+        let recordings_for_removed = self.recordings.clone();
+        let known_producers_for_removed = self.known_producers.clone();
+        signaller.connect("producer-removed", false, move |args| {
+            let producer_id = args[1].get::<String>().unwrap();
+
+            if let Ok(mut known_producers) = known_producers_for_removed.lock() {
+                known_producers.remove(&producer_id);
+            }
+            if let Ok(mut frames) = video_frames_for_removed.lock() {
+                frames.remove(&producer_id);
+            }
+            if let Ok(mut stats) = stats_for_removed.lock() {
+                stats.remove(&producer_id);
+            }
+            if let Ok(mut tees) = producer_tees_for_removed.lock() {
+                tees.remove(&producer_id);
+            }
+            if let Ok(mut producer_sessions) = producer_sessions_for_removed.lock() {
+                if let Some(session_id) = producer_sessions.remove(&producer_id) {
+                    if let Ok(mut session_producers) = session_producers_for_removed.lock() {
+                        session_producers.remove(&session_id);
+                    }
+                    if let Ok(mut webrtcbins) = webrtcbins_for_removed.lock() {
+                        webrtcbins.remove(&session_id);
+                    }
+                }
+            }
+
+            // producer自体が消えるのでteeごと無くなる。録画中だった場合、decodeブランチ
+            // (teeを含む)を先に/並行してNullに落としてしまうと、録画ブランチのEOSが
+            // muxer/filesinkへ届く前にteeが消えてレースになり、finalize_recording_branchの
+            // EOS待ちが意味を成さなくなる。そのため、録画中はdecodeブランチの要素を
+            // finalize_recording_branchに渡し、録画側のEOS到達が確認できてから初めて
+            // 両方をまとめてNull化/削除させる。録画していなければ従来通り即座に片付ける。
+            if let Some(pipeline) = pipeline_weak_for_removed.upgrade() {
+                let decode_elements = producer_branches_for_removed
+                    .lock()
+                    .ok()
+                    .and_then(|mut branches| branches.remove(&producer_id))
+                    .unwrap_or_default();
+
+                let branch = recordings_for_removed
+                    .lock()
+                    .ok()
+                    .and_then(|mut recordings| recordings.remove(&producer_id));
+
+                if let Some(branch) = branch {
+                    if let Ok(mut logs) = logs_for_removed.lock() {
+                        logs.push(format!(
+                            "⚠️ 録画中にproducerが退出しました。ファイルを確定させています: producer_id={}",
+                            producer_id
+                        ));
+                    }
+                    finalize_recording_branch(
+                        &pipeline,
+                        branch,
+                        logs_for_removed.clone(),
+                        producer_id.clone(),
+                        decode_elements,
+                    );
+                } else {
+                    for element in &decode_elements {
+                        let _ = element.set_state(gst::State::Null);
+                    }
+                    let refs: Vec<&gst::Element> = decode_elements.iter().collect();
+                    let _ = pipeline.remove_many(refs);
+                }
+            }
+
+            if let Ok(mut logs) = logs_for_removed.lock() {
+                logs.push(format!("👋 Producer退出: producer_id={}", producer_id));
+            }
+            None
+        });
+
         let logs = self.logs.clone();
         signaller.connect("session-requested", false, move |args| {
             let session_id = args[1].get::<String>().unwrap();
@@ -93,9 +445,23 @@ impl WebRtcApp {
         });
 
         let logs = self.logs.clone();
+        let producer_sessions_for_started = self.producer_sessions.clone();
+        let session_producers_for_started = self.session_producers.clone();
+        let known_producers_for_started = self.known_producers.clone();
         signaller.connect("session-started", false, move |args| {
             let session_id = args[1].get::<String>().unwrap();
             let peer_id = args[2].get::<String>().unwrap();
+            // webrtcsrcのsession-startedが渡すpeer_idは、このビューアがconsumerとして
+            // 接続した相手のidであり、producer_idと一致している前提でcontrol channelの
+            // 送信先キーに使っている。producer-addedで見たproducer_id集合と食い違って
+            // いないかを実行時に検証し、崩れていればログに警告を残す。
+            warn_if_unknown_producer(&known_producers_for_started, &logs, "session-startedのpeer_id", &peer_id);
+            if let Ok(mut producer_sessions) = producer_sessions_for_started.lock() {
+                producer_sessions.insert(peer_id.clone(), session_id.clone());
+            }
+            if let Ok(mut session_producers) = session_producers_for_started.lock() {
+                session_producers.insert(session_id.clone(), peer_id.clone());
+            }
             if let Ok(mut logs) = logs.lock() {
                 logs.push(format!("✅ セッション開始: peer_id={}, session_id={}", peer_id, session_id));
             }
@@ -103,9 +469,34 @@ impl WebRtcApp {
         });
 
         let logs = self.logs.clone();
+        let control_channels = self.control_channels.clone();
+        let webrtcbins_for_ready = self.webrtcbins.clone();
         signaller.connect("webrtcbin-ready", false, move |args| {
+            let session_id = args[1].get::<String>().unwrap();
             let webrtcbin = args[2].get::<gst::Element>().unwrap();
             webrtcbin.set_property("latency", 20u32);
+
+            if let Ok(mut webrtcbins) = webrtcbins_for_ready.lock() {
+                webrtcbins.insert(session_id.clone(), webrtcbin.clone());
+            }
+
+            // enable-control-data-channel=trueでwebrtcsrc側が開く"control"チャンネルを捕まえる
+            let control_channels_for_dc = control_channels.clone();
+            let session_id_for_dc = session_id.clone();
+            let logs_for_dc = logs.clone();
+            webrtcbin.connect("on-data-channel", false, move |args| {
+                let channel = args[1].get::<gst_webrtc::WebRTCDataChannel>().unwrap();
+                if channel.property::<String>("label") == "control" {
+                    if let Ok(mut channels) = control_channels_for_dc.lock() {
+                        channels.insert(session_id_for_dc.clone(), channel);
+                    }
+                    if let Ok(mut logs) = logs_for_dc.lock() {
+                        logs.push(format!("🕹️ Controlチャンネル開通: session_id={}", session_id_for_dc));
+                    }
+                }
+                None
+            });
+
             if let Ok(mut logs) = logs.lock() {
                 logs.push("🎬 WebRTCBin ready - 低遅延設定を適用しました".to_string());
             }
@@ -113,8 +504,17 @@ impl WebRtcApp {
         });
 
         // pad-addedシグナル: videoとaudioのパッドを動的に接続
-        let video_frame = self.video_frame.clone();
+        // webrtcsrcはproducerごとに "video_<producer-id>" / "audio_<producer-id>"
+        // という名前でパッドを出すので、そこからproducer idを取り出してキーにする。
+        // このpad名の規則はインストールされているgst-plugins-rsのバージョンに依存する
+        // 前提であり、確実に検証できる手段がこの環境には無いため、producer-addedで
+        // 通知済みのproducer id集合(known_producers)と突き合わせて前提が崩れていないか
+        // 実行時に検知できるようにしておく(一致しなければログに警告を出す)。
+        let video_frames = self.video_frames.clone();
+        let producer_branches = self.producer_branches.clone();
+        let producer_tees = self.producer_tees.clone();
         let logs_for_pad = self.logs.clone();
+        let known_producers_for_pad = self.known_producers.clone();
         webrtcsrc.connect_pad_added(move |webrtcsrc, pad| {
             let Some(pipeline) = webrtcsrc
                 .parent()
@@ -123,9 +523,13 @@ impl WebRtcApp {
                 return;
             };
 
-            if pad.name().starts_with("audio") {
+            let pad_name = pad.name();
+
+            if let Some(producer_id) = pad_name.strip_prefix("audio_") {
+                let producer_id = producer_id.to_string();
+                warn_if_unknown_producer(&known_producers_for_pad, &logs_for_pad, &pad_name, &producer_id);
                 if let Ok(mut logs) = logs_for_pad.lock() {
-                    logs.push("🔊 Audio pad追加".to_string());
+                    logs.push(format!("🔊 Audio pad追加: producer_id={}", producer_id));
                 }
 
                 let audioconvert = gst::ElementFactory::make("audioconvert").build().unwrap();
@@ -139,18 +543,38 @@ impl WebRtcApp {
                 let audiosink = gst::ElementFactory::make("autoaudiosink")
                     .build()
                     .unwrap();
+                // 録画(record-while-viewing)用に後から分岐できるよう、再生経路にteeを挟んでおく
+                let tee = gst::ElementFactory::make("tee")
+                    .property("allow-not-linked", true)
+                    .build()
+                    .unwrap();
 
-                pipeline.add_many([&audioconvert, &audioresample, &queue, &audiosink]).unwrap();
+                pipeline
+                    .add_many([&audioconvert, &audioresample, &queue, &tee, &audiosink])
+                    .unwrap();
                 pad.link(&audioconvert.static_pad("sink").unwrap()).unwrap();
-                gst::Element::link_many([&audioconvert, &audioresample, &queue, &audiosink]).unwrap();
+                gst::Element::link_many([&audioconvert, &audioresample, &queue, &tee, &audiosink]).unwrap();
 
                 audiosink.sync_state_with_parent().unwrap();
+                tee.sync_state_with_parent().unwrap();
                 queue.sync_state_with_parent().unwrap();
                 audioresample.sync_state_with_parent().unwrap();
                 audioconvert.sync_state_with_parent().unwrap();
-            } else if pad.name().starts_with("video") {
+
+                if let Ok(mut tees) = producer_tees.lock() {
+                    tees.entry(producer_id.clone()).or_default().audio = Some(tee.clone());
+                }
+                if let Ok(mut branches) = producer_branches.lock() {
+                    branches
+                        .entry(producer_id)
+                        .or_default()
+                        .extend([audioconvert, audioresample, queue, tee, audiosink]);
+                }
+            } else if let Some(producer_id) = pad_name.strip_prefix("video_") {
+                let producer_id = producer_id.to_string();
+                warn_if_unknown_producer(&known_producers_for_pad, &logs_for_pad, &pad_name, &producer_id);
                 if let Ok(mut logs) = logs_for_pad.lock() {
-                    logs.push("🎥 Video pad追加".to_string());
+                    logs.push(format!("🎥 Video pad追加: producer_id={}", producer_id));
                 }
 
                 let videoconvert = gst::ElementFactory::make("videoconvert").build().unwrap();
@@ -171,45 +595,120 @@ impl WebRtcApp {
                     )
                     .build();
 
-                let video_frame_clone = video_frame.clone();
+                let video_frames_clone = video_frames.clone();
+                let producer_id_for_sample = producer_id.clone();
                 appsink.set_callbacks(
                     gst_app::AppSinkCallbacks::builder()
                         .new_sample(move |appsink| {
                             let sample = appsink.pull_sample().map_err(|_| gst::FlowError::Eos)?;
                             let buffer = sample.buffer().ok_or(gst::FlowError::Error)?;
                             let caps = sample.caps().ok_or(gst::FlowError::Error)?;
-                            
+
                             let video_info = gst_video::VideoInfo::from_caps(caps)
                                 .map_err(|_| gst::FlowError::Error)?;
-                            
+
                             let map = buffer.map_readable().map_err(|_| gst::FlowError::Error)?;
-                            
-                            if let Ok(mut frame) = video_frame_clone.lock() {
-                                *frame = Some(VideoFrame {
-                                    width: video_info.width() as usize,
-                                    height: video_info.height() as usize,
-                                    data: map.as_slice().to_vec(),
-                                });
+
+                            if let Ok(mut frames) = video_frames_clone.lock() {
+                                frames.insert(
+                                    producer_id_for_sample.clone(),
+                                    VideoFrame {
+                                        width: video_info.width() as usize,
+                                        height: video_info.height() as usize,
+                                        data: map.as_slice().to_vec(),
+                                    },
+                                );
                             }
-                            
+
                             Ok(gst::FlowSuccess::Ok)
                         })
                         .build()
                 );
 
-                pipeline.add_many([&videoconvert, &videoscale, &queue, appsink.upcast_ref()]).unwrap();
+                // 録画(record-while-viewing)用に後から分岐できるよう、再生経路にteeを挟んでおく
+                let tee = gst::ElementFactory::make("tee")
+                    .property("allow-not-linked", true)
+                    .build()
+                    .unwrap();
+
+                pipeline
+                    .add_many([&videoconvert, &videoscale, &queue, &tee, appsink.upcast_ref()])
+                    .unwrap();
                 pad.link(&videoconvert.static_pad("sink").unwrap()).unwrap();
-                gst::Element::link_many([&videoconvert, &videoscale, &queue, appsink.upcast_ref()]).unwrap();
+                gst::Element::link_many([&videoconvert, &videoscale, &queue, &tee, appsink.upcast_ref()]).unwrap();
 
                 appsink.sync_state_with_parent().unwrap();
+                tee.sync_state_with_parent().unwrap();
                 queue.sync_state_with_parent().unwrap();
                 videoscale.sync_state_with_parent().unwrap();
                 videoconvert.sync_state_with_parent().unwrap();
+
+                if let Ok(mut tees) = producer_tees.lock() {
+                    tees.entry(producer_id.clone()).or_default().video = Some(tee.clone());
+                }
+                if let Ok(mut branches) = producer_branches.lock() {
+                    branches.entry(producer_id).or_default().extend([
+                        videoconvert,
+                        videoscale,
+                        queue,
+                        tee,
+                        appsink.upcast(),
+                    ]);
+                }
             }
         });
 
-        // パイプライン起動
-        pipeline.set_state(gst::State::Playing)?;
+        // ネットワーククロックへの同期待ちはブロッキング呼び出し(wait_for_sync)を伴うため、
+        // egui描画ループを止めないようバックグラウンドスレッドで行い、同期完了後(またはタイム
+        // アウト後)にそのスレッドからPlayingへ遷移させる。既定クロックのままで良ければ、
+        // これまで通りここで同期的にPlayingへ上げる。
+        if self.settings.clock_mode == ClockMode::PipelineDefault {
+            pipeline.set_state(gst::State::Playing)?;
+        } else {
+            pipeline.set_state(gst::State::Paused)?;
+
+            let pipeline_for_clock = pipeline.clone();
+            let logs_for_clock = self.logs.clone();
+            let clock_mode = self.settings.clock_mode;
+            let ntp_host = self.settings.ntp_host.clone();
+            let ptp_domain = self.settings.ptp_domain;
+            let timeout_secs = self.settings.clock_sync_timeout_secs;
+
+            std::thread::spawn(move || {
+                match build_network_clock(clock_mode, &ntp_host, ptp_domain) {
+                    Ok(Some(clock)) => {
+                        if let Ok(mut logs) = logs_for_clock.lock() {
+                            logs.push(format!(
+                                "🕒 {}との時刻同期を待っています(タイムアウト{}秒)...",
+                                clock_mode.label(),
+                                timeout_secs
+                            ));
+                        }
+
+                        let timeout = gst::ClockTime::from_seconds(timeout_secs as u64);
+                        if clock.wait_for_sync(timeout) {
+                            pipeline_for_clock.use_clock(Some(&clock));
+                            if let Ok(mut logs) = logs_for_clock.lock() {
+                                logs.push("✅ ネットワーククロックに同期しました".to_string());
+                            }
+                        } else if let Ok(mut logs) = logs_for_clock.lock() {
+                            logs.push(
+                                "⚠️ クロック同期がタイムアウトしました。パイプライン既定のクロックを使用します"
+                                    .to_string(),
+                            );
+                        }
+                    }
+                    Ok(None) => {}
+                    Err(err) => {
+                        if let Ok(mut logs) = logs_for_clock.lock() {
+                            logs.push(format!("❌ ネットワーククロックの作成に失敗しました: {}", err));
+                        }
+                    }
+                }
+
+                let _ = pipeline_for_clock.set_state(gst::State::Playing);
+            });
+        }
 
         // バスメッセージ処理用のスレッドを起動
         let bus = pipeline.bus().expect("Pipeline should have a bus");
@@ -246,6 +745,108 @@ impl WebRtcApp {
             }
         });
 
+        // webrtcbinのget-statsを定期的にポーリングし、回線品質を更新するスレッド
+        let webrtcbins_for_stats = self.webrtcbins.clone();
+        let session_producers_for_stats = self.session_producers.clone();
+        let stats_for_poll = self.stats.clone();
+        let pipeline_weak_for_stats = pipeline.downgrade();
+
+        std::thread::spawn(move || {
+            let mut prev_inbound: HashMap<String, InboundRtpSnapshot> = HashMap::new();
+
+            while pipeline_weak_for_stats.upgrade().is_some() {
+                std::thread::sleep(STATS_POLL_INTERVAL);
+
+                let sessions: Vec<(String, gst::Element)> = match webrtcbins_for_stats.lock() {
+                    Ok(bins) => bins.iter().map(|(k, v)| (k.clone(), v.clone())).collect(),
+                    Err(_) => continue,
+                };
+
+                for (session_id, webrtcbin) in sessions {
+                    let Some(producer_id) = session_producers_for_stats
+                        .lock()
+                        .ok()
+                        .and_then(|m| m.get(&session_id).cloned())
+                    else {
+                        continue;
+                    };
+
+                    let promise = gst::Promise::new();
+                    webrtcbin.emit_by_name::<()>("get-stats", &[&None::<gst::Pad>, &promise]);
+                    promise.wait();
+                    let Some(reply) = promise.get_reply() else {
+                        continue;
+                    };
+
+                    let mut bitrate_bps = 0.0;
+                    let mut packet_loss_pct = 0.0;
+                    let mut rtt_ms = 0.0;
+                    let mut jitter_ms = 0.0;
+
+                    for field_name in reply.fields() {
+                        let Ok(entry) = reply.get::<gst::Structure>(field_name) else {
+                            continue;
+                        };
+
+                        // "type"フィールドはGstWebRTCStatsType列挙型であり文字列ではないため、
+                        // get::<String>では常にErrになり候補がヒットしない。列挙型として読む。
+                        match entry.get::<gst_webrtc::WebRTCStatsType>("type") {
+                            Ok(gst_webrtc::WebRTCStatsType::InboundRtp) => {
+                                let bytes_received = entry.get::<u64>("bytes-received").unwrap_or(0);
+                                let packets_lost =
+                                    entry.get::<i32>("packets-lost").unwrap_or(0).max(0) as u64;
+                                let packets_received =
+                                    entry.get::<u64>("packets-received").unwrap_or(0);
+
+                                // 1セッションにaudio/videoのinbound-rtpが並ぶため、統計ID単位で前回値を持つ
+                                let snapshot = prev_inbound
+                                    .entry(format!("{}/{}", session_id, field_name))
+                                    .or_default();
+                                if let Some(prev_at) = snapshot.at {
+                                    let elapsed = prev_at.elapsed().as_secs_f64();
+                                    if elapsed > 0.0 && bytes_received >= snapshot.bytes_received {
+                                        bitrate_bps += (bytes_received - snapshot.bytes_received) as f64
+                                            * 8.0
+                                            / elapsed;
+                                    }
+                                }
+                                snapshot.bytes_received = bytes_received;
+                                snapshot.at = Some(Instant::now());
+
+                                let total = packets_lost + packets_received;
+                                if total > 0 {
+                                    packet_loss_pct = packets_lost as f64 / total as f64 * 100.0;
+                                }
+
+                                // jitterは秒単位のf64で入っているのでrtt同様msに換算する
+                                if let Ok(jitter) = entry.get::<f64>("jitter") {
+                                    jitter_ms = jitter * 1000.0;
+                                }
+                            }
+                            Ok(gst_webrtc::WebRTCStatsType::CandidatePair) => {
+                                if let Ok(rtt) = entry.get::<f64>("current-round-trip-time") {
+                                    rtt_ms = rtt * 1000.0;
+                                }
+                            }
+                            _ => {}
+                        }
+                    }
+
+                    if let Ok(mut stats_map) = stats_for_poll.lock() {
+                        let entry = stats_map.entry(producer_id).or_default();
+                        entry.bitrate_bps = bitrate_bps;
+                        entry.packet_loss_pct = packet_loss_pct;
+                        entry.rtt_ms = rtt_ms;
+                        entry.jitter_ms = jitter_ms;
+                        entry.bitrate_history.push_back(bitrate_bps);
+                        while entry.bitrate_history.len() > STATS_HISTORY_LEN {
+                            entry.bitrate_history.pop_front();
+                        }
+                    }
+                }
+            }
+        });
+
         self.pipeline = Some(pipeline);
         self.is_running = true;
         self.add_log("▶️ パイプライン開始".to_string());
@@ -259,9 +860,389 @@ impl WebRtcApp {
             self.is_running = false;
             self.add_log("⏹️ パイプライン停止".to_string());
         }
+
+        if let Ok(mut frames) = self.video_frames.lock() {
+            frames.clear();
+        }
+        if let Ok(mut branches) = self.producer_branches.lock() {
+            branches.clear();
+        }
+        if let Ok(mut control_channels) = self.control_channels.lock() {
+            control_channels.clear();
+        }
+        if let Ok(mut producer_sessions) = self.producer_sessions.lock() {
+            producer_sessions.clear();
+        }
+        if let Ok(mut session_producers) = self.session_producers.lock() {
+            session_producers.clear();
+        }
+        if let Ok(mut webrtcbins) = self.webrtcbins.lock() {
+            webrtcbins.clear();
+        }
+        if let Ok(mut stats) = self.stats.lock() {
+            stats.clear();
+        }
+        if let Ok(mut tees) = self.producer_tees.lock() {
+            tees.clear();
+        }
+        if let Ok(mut recordings) = self.recordings.lock() {
+            recordings.clear();
+        }
+        self.video_textures.clear();
+        self.focused_producer = None;
+    }
+
+    /// 指定producerのtee(video/audio)からmatroskamux+filesinkへ分岐して録画を開始する
+    fn start_recording(&self, producer_id: &str) -> Result<(), Error> {
+        let Some(pipeline) = self.pipeline.clone() else {
+            anyhow::bail!("パイプラインが起動していません");
+        };
+
+        let tees = self
+            .producer_tees
+            .lock()
+            .ok()
+            .and_then(|tees| tees.get(producer_id).cloned())
+            .unwrap_or_default();
+
+        if tees.video.is_none() && tees.audio.is_none() {
+            anyhow::bail!("producer {} の再生経路がまだ準備できていません", producer_id);
+        }
+
+        let muxer = gst::ElementFactory::make("matroskamux").build()?;
+        let timestamp = std::time::SystemTime::now()
+            .duration_since(std::time::UNIX_EPOCH)
+            .map(|d| d.as_secs())
+            .unwrap_or(0);
+        let path = format!("{}/{}-{}.mkv", self.settings.recording_dir, producer_id, timestamp);
+        let filesink = gst::ElementFactory::make("filesink")
+            .property("location", path.as_str())
+            .build()?;
+
+        pipeline.add_many([&muxer, &filesink])?;
+        gst::Element::link(&muxer, &filesink)?;
+
+        let mut video_elements = Vec::new();
+        let mut video_tee_pad = None;
+        if let Some(video_tee) = &tees.video {
+            let convert = gst::ElementFactory::make("videoconvert").build()?;
+            let encoder = gst::ElementFactory::make("x264enc")
+                .property_from_str("tune", "zerolatency")
+                .build()?;
+            let queue = gst::ElementFactory::make("queue").build()?;
+
+            pipeline.add_many([&convert, &encoder, &queue])?;
+            gst::Element::link_many([&convert, &encoder, &queue])?;
+            gst::Element::link(&queue, &muxer)?;
+
+            let tee_pad = video_tee
+                .request_pad_simple("src_%u")
+                .ok_or_else(|| anyhow::anyhow!("video teeのsrcパッド確保に失敗しました"))?;
+            tee_pad.link(&convert.static_pad("sink").unwrap())?;
+
+            convert.sync_state_with_parent()?;
+            encoder.sync_state_with_parent()?;
+            queue.sync_state_with_parent()?;
+
+            video_elements = vec![convert, encoder, queue];
+            video_tee_pad = Some(tee_pad);
+        }
+
+        let mut audio_elements = Vec::new();
+        let mut audio_tee_pad = None;
+        if let Some(audio_tee) = &tees.audio {
+            let convert = gst::ElementFactory::make("audioconvert").build()?;
+            let encoder = gst::ElementFactory::make("opusenc").build()?;
+            let queue = gst::ElementFactory::make("queue").build()?;
+
+            pipeline.add_many([&convert, &encoder, &queue])?;
+            gst::Element::link_many([&convert, &encoder, &queue])?;
+            gst::Element::link(&queue, &muxer)?;
+
+            let tee_pad = audio_tee
+                .request_pad_simple("src_%u")
+                .ok_or_else(|| anyhow::anyhow!("audio teeのsrcパッド確保に失敗しました"))?;
+            tee_pad.link(&convert.static_pad("sink").unwrap())?;
+
+            convert.sync_state_with_parent()?;
+            encoder.sync_state_with_parent()?;
+            queue.sync_state_with_parent()?;
+
+            audio_elements = vec![convert, encoder, queue];
+            audio_tee_pad = Some(tee_pad);
+        }
+
+        muxer.sync_state_with_parent()?;
+        filesink.sync_state_with_parent()?;
+
+        if let Ok(mut recordings) = self.recordings.lock() {
+            recordings.insert(
+                producer_id.to_string(),
+                RecordingBranch {
+                    video_elements,
+                    audio_elements,
+                    video_tee_pad,
+                    audio_tee_pad,
+                    muxer,
+                    filesink,
+                },
+            );
+        }
+
+        self.add_log(format!("⏺️ 録画開始: producer_id={}, path={}", producer_id, path));
+        Ok(())
+    }
+
+    /// teeのsrcパッドをブロックした上でEOSを送り込み、ファイルを正しく閉じてから分岐を外す
+    fn stop_recording(&self, producer_id: &str) {
+        let Some(pipeline) = self.pipeline.clone() else {
+            return;
+        };
+
+        let Some(branch) = self
+            .recordings
+            .lock()
+            .ok()
+            .and_then(|mut recordings| recordings.remove(producer_id))
+        else {
+            return;
+        };
+
+        finalize_recording_branch(
+            &pipeline,
+            branch,
+            self.logs.clone(),
+            producer_id.to_string(),
+            Vec::new(),
+        );
+        self.add_log(format!("⏺️→⏹️ 録画停止処理を開始しました: producer_id={}", producer_id));
     }
 }
 
+/// egui::KeyをGstNavigation(X11キーシム風のキー名)が期待する語彙にマッピングする。
+/// `{:?}`によるenumのDebug名(例: "Num1", "ArrowLeft")はGStreamer側のキー名とは
+/// 別物なので使わず、対応が分かっているキーだけ明示的に変換する。未対応のキーは
+/// 誤ったキー名を送らないようNoneを返して送信をスキップする。
+fn gst_navigation_key_name(key: egui::Key) -> Option<String> {
+    use egui::Key;
+
+    let name = match key {
+        Key::ArrowLeft => "Left",
+        Key::ArrowRight => "Right",
+        Key::ArrowUp => "Up",
+        Key::ArrowDown => "Down",
+        Key::Escape => "Escape",
+        Key::Tab => "Tab",
+        Key::Backspace => "BackSpace",
+        Key::Enter => "Return",
+        Key::Space => "space",
+        Key::Insert => "Insert",
+        Key::Delete => "Delete",
+        Key::Home => "Home",
+        Key::End => "End",
+        Key::PageUp => "Page_Up",
+        Key::PageDown => "Page_Down",
+        Key::Minus => "minus",
+        Key::Equals => "equal",
+        Key::Num0 => "0",
+        Key::Num1 => "1",
+        Key::Num2 => "2",
+        Key::Num3 => "3",
+        Key::Num4 => "4",
+        Key::Num5 => "5",
+        Key::Num6 => "6",
+        Key::Num7 => "7",
+        Key::Num8 => "8",
+        Key::Num9 => "9",
+        Key::F1 => "F1",
+        Key::F2 => "F2",
+        Key::F3 => "F3",
+        Key::F4 => "F4",
+        Key::F5 => "F5",
+        Key::F6 => "F6",
+        Key::F7 => "F7",
+        Key::F8 => "F8",
+        Key::F9 => "F9",
+        Key::F10 => "F10",
+        Key::F11 => "F11",
+        Key::F12 => "F12",
+        Key::A => "a",
+        Key::B => "b",
+        Key::C => "c",
+        Key::D => "d",
+        Key::E => "e",
+        Key::F => "f",
+        Key::G => "g",
+        Key::H => "h",
+        Key::I => "i",
+        Key::J => "j",
+        Key::K => "k",
+        Key::L => "l",
+        Key::M => "m",
+        Key::N => "n",
+        Key::O => "o",
+        Key::P => "p",
+        Key::Q => "q",
+        Key::R => "r",
+        Key::S => "s",
+        Key::T => "t",
+        Key::U => "u",
+        Key::V => "v",
+        Key::W => "w",
+        Key::X => "x",
+        Key::Y => "y",
+        Key::Z => "z",
+        _ => return None,
+    };
+
+    Some(name.to_string())
+}
+
+/// サーバーURI/room・peer idを、該当プロパティがある場合のみsignallerへ設定する。
+/// Defaultバックエンド(webrtcsrc組み込みsignaller)でも適用できるよう、差し替え後に
+/// 実際に使われるsignallerオブジェクトに対して呼び出す想定の関数にしてある。
+fn apply_signaller_string_props(signaller: &gst::glib::Object, settings: &Settings) {
+    let string_props: [(&str, &str); 3] = [
+        ("uri", &settings.server_uri),
+        ("room-id", &settings.room_id),
+        ("producer-peer-id", &settings.producer_peer_id),
+    ];
+
+    for (name, value) in string_props {
+        if !value.is_empty() && signaller.has_property(name, None) {
+            signaller.set_property(name, value);
+        }
+    }
+}
+
+/// pad名の"video_"/"audio_"プレフィックスを剥がして取り出したproducer_id(または
+/// session-startedのpeer_id)が、producer-addedで実際に通知された値と一致しているか
+/// 検証する。一致しなければproducer idの取り出し方についての前提(pad名なら
+/// video_<producer-id>/audio_<producer-id>)が崩れている可能性が高く、フレームと
+/// stats/テアダウンのキーがずれて食い違うため、画面が固まるより先に気付けるよう
+/// ログに警告を残す。`source`はIDの取り出し元(pad名など)で、警告文に含める。
+fn warn_if_unknown_producer(
+    known_producers: &Arc<Mutex<HashSet<ProducerId>>>,
+    logs: &Arc<Mutex<Vec<String>>>,
+    source: &str,
+    producer_id: &str,
+) {
+    let is_known = known_producers
+        .lock()
+        .map(|known| known.contains(producer_id))
+        .unwrap_or(true);
+    if !is_known {
+        if let Ok(mut logs) = logs.lock() {
+            logs.push(format!(
+                "⚠️ \"{}\"から取り出したproducer_id=\"{}\"がproducer-addedの通知一覧に無く、\
+                 producer idの取り出し方の前提が崩れている可能性があります",
+                source, producer_id
+            ));
+        }
+    }
+}
+
+/// `clock_mode`に従ってNTP/PTPクロックを作る。パイプライン既定のままで良ければ`None`を返す。
+fn build_network_clock(
+    clock_mode: ClockMode,
+    ntp_host: &str,
+    ptp_domain: u32,
+) -> Result<Option<gst::Clock>, Error> {
+    match clock_mode {
+        ClockMode::PipelineDefault => Ok(None),
+        ClockMode::Ntp => {
+            let clock = gst_net::NtpClock::new(Some("ntp-clock"), ntp_host, 123, gst::ClockTime::ZERO);
+            Ok(Some(clock.upcast()))
+        }
+        ClockMode::Ptp => {
+            gst_net::ptp_clock_init(None, &[]);
+            let clock = gst_net::PtpClock::new(Some("ptp-clock"), ptp_domain)?;
+            Ok(Some(clock.upcast()))
+        }
+    }
+}
+
+/// 録画ブランチを安全に閉じる。tee側のsrcパッドをブロックしてunlinkしてからEOSを
+/// encoder/queue側に送り込み、それがmuxerを経由してfilesinkのsinkパッドに到達したのを
+/// pad probeで検知してから初めてNullに落として`pipeline`から取り外す。EOSを送った直後に
+/// 同期的にNullへ落とすと、matroskamuxがヘッダ/キューを書き出す前に破棄されてしまい
+/// ファイルが壊れるため、必ずfilesink側のEOS到達を待ってから後始末する。
+fn finalize_recording_branch(
+    pipeline: &gst::Pipeline,
+    branch: RecordingBranch,
+    logs: Arc<Mutex<Vec<String>>>,
+    producer_id: String,
+    extra_elements_to_remove_on_finalize: Vec<gst::Element>,
+) {
+    let Some(filesink_sink_pad) = branch.filesink.static_pad("sink") else {
+        return;
+    };
+
+    // producer-removedでdecodeブランチ(teeを含む)がまだ生きている場合、録画ブランチの
+    // EOSがfilesinkへ届くより先にそちらをNullへ落としてしまうとteeがレース的に消えて
+    // しまうため、ここに渡してもらい、録画側のEOS確定と同じタイミングでまとめて片付ける。
+    let mut all_elements: Vec<gst::Element> = Vec::new();
+    all_elements.extend(branch.video_elements.iter().cloned());
+    all_elements.extend(branch.audio_elements.iter().cloned());
+    all_elements.push(branch.muxer.clone());
+    all_elements.push(branch.filesink.clone());
+    all_elements.extend(extra_elements_to_remove_on_finalize);
+
+    let teardown_pipeline = pipeline.clone();
+    let teardown_logs = logs.clone();
+    let teardown_producer_id = producer_id.clone();
+    filesink_sink_pad.add_probe(gst::PadProbeType::EVENT_DOWNSTREAM, move |_pad, info| {
+        let is_eos = info
+            .event()
+            .map(|event| event.type_() == gst::EventType::Eos)
+            .unwrap_or(false);
+        if !is_eos {
+            return gst::PadProbeReturn::Ok;
+        }
+
+        for element in &all_elements {
+            let _ = element.set_state(gst::State::Null);
+        }
+        let refs: Vec<&gst::Element> = all_elements.iter().collect();
+        let _ = teardown_pipeline.remove_many(refs);
+
+        if let Ok(mut logs) = teardown_logs.lock() {
+            logs.push(format!(
+                "⏹️ 録画ファイルを確定しました: producer_id={}",
+                teardown_producer_id
+            ));
+        }
+
+        gst::PadProbeReturn::Remove
+    });
+
+    // tee側のsrcパッドを実際にブロックし、ブロックできたコールバック内でunlink・パッド解放・
+    // EOS送出までを行ってから初めてプローブを外す(BLOCK_DOWNSTREAMはコールバックが
+    // `Remove`以外を返す限りブロックされたままになる)
+    for (tee_pad, first_element) in [
+        (branch.video_tee_pad, branch.video_elements.into_iter().next()),
+        (branch.audio_tee_pad, branch.audio_elements.into_iter().next()),
+    ] {
+        let (Some(tee_pad), Some(first_element)) = (tee_pad, first_element) else {
+            continue;
+        };
+
+        tee_pad.add_probe(gst::PadProbeType::BLOCK_DOWNSTREAM, move |pad, _info| {
+            if let Some(peer) = pad.peer() {
+                let _ = pad.unlink(&peer);
+            }
+            if let Some(tee) = pad.parent_element() {
+                let _ = tee.release_request_pad(pad);
+            }
+            if let Some(sink_pad) = first_element.static_pad("sink") {
+                sink_pad.send_event(gst::event::Eos::new());
+            }
+            gst::PadProbeReturn::Remove
+        });
+    }
+
+}
+
 impl eframe::App for WebRtcApp {
     fn update(&mut self, ctx: &egui::Context, _frame: &mut eframe::Frame) {
         // 定期的に再描画をリクエスト
@@ -286,26 +1267,271 @@ impl eframe::App for WebRtcApp {
 
             ui.separator();
 
-            // ビデオ表示エリア
+            // シグナリング設定 - 実行中は接続先を変えられないため無効化する
+            egui::CollapsingHeader::new("⚙️ シグナリング設定").show(ui, |ui| {
+                ui.add_enabled_ui(!self.is_running, |ui| {
+                    egui::ComboBox::from_label("バックエンド")
+                        .selected_text(self.settings.backend.label())
+                        .show_ui(ui, |ui| {
+                            for backend in SignallingBackend::ALL {
+                                ui.selectable_value(&mut self.settings.backend, backend, backend.label());
+                            }
+                        });
+
+                    ui.horizontal(|ui| {
+                        ui.label("サーバーURI:");
+                        ui.text_edit_singleline(&mut self.settings.server_uri);
+                    });
+                    ui.horizontal(|ui| {
+                        ui.label("STUNサーバー:");
+                        ui.text_edit_singleline(&mut self.settings.stun_server);
+                    });
+                    ui.horizontal(|ui| {
+                        ui.label("TURNサーバー:");
+                        ui.text_edit_singleline(&mut self.settings.turn_server);
+                    });
+                    ui.horizontal(|ui| {
+                        ui.label("Room ID:");
+                        ui.text_edit_singleline(&mut self.settings.room_id);
+                    });
+                    ui.horizontal(|ui| {
+                        ui.label("Producer Peer ID:");
+                        ui.text_edit_singleline(&mut self.settings.producer_peer_id);
+                    });
+
+                    ui.separator();
+
+                    egui::ComboBox::from_label("同期クロック (RFC 7273)")
+                        .selected_text(self.settings.clock_mode.label())
+                        .show_ui(ui, |ui| {
+                            for mode in ClockMode::ALL {
+                                ui.selectable_value(&mut self.settings.clock_mode, mode, mode.label());
+                            }
+                        });
+
+                    match self.settings.clock_mode {
+                        ClockMode::PipelineDefault => {}
+                        ClockMode::Ntp => {
+                            ui.horizontal(|ui| {
+                                ui.label("NTPホスト:");
+                                ui.text_edit_singleline(&mut self.settings.ntp_host);
+                            });
+                        }
+                        ClockMode::Ptp => {
+                            ui.horizontal(|ui| {
+                                ui.label("PTPドメイン:");
+                                ui.add(egui::DragValue::new(&mut self.settings.ptp_domain));
+                            });
+                        }
+                    }
+
+                    if self.settings.clock_mode != ClockMode::PipelineDefault {
+                        ui.horizontal(|ui| {
+                            ui.label("同期タイムアウト(秒):");
+                            ui.add(egui::DragValue::new(&mut self.settings.clock_sync_timeout_secs));
+                        });
+                    }
+
+                    ui.separator();
+
+                    ui.horizontal(|ui| {
+                        ui.label("録画の保存先:");
+                        ui.text_edit_singleline(&mut self.settings.recording_dir);
+                    });
+                });
+            });
+
+            ui.separator();
+
+            // ビデオ表示エリア - producerごとにタイルとして並べる
             ui.heading("ビデオ");
-            
-            if let Ok(frame_guard) = self.video_frame.lock() {
-                if let Some(frame) = frame_guard.as_ref() {
-                    let color_image = egui::ColorImage::from_rgba_unmultiplied(
-                        [frame.width, frame.height],
-                        &frame.data,
-                    );
-                    
-                    let texture = ctx.load_texture(
-                        "video-frame",
-                        color_image,
-                        egui::TextureOptions::LINEAR,
-                    );
-                    
-                    ui.image(&texture);
-                    self.video_texture = Some(texture);
-                } else {
+
+            // タイル描画中に録画開始/停止などselfのメソッドを呼ぶため、ロックを保持し続けず、
+            // 表示に必要な分だけ先にコピーしてロックを早期に解放しておく
+            let tile_frames: Vec<(ProducerId, usize, usize, Vec<u8>)> = self
+                .video_frames
+                .lock()
+                .map(|frames| {
+                    frames
+                        .iter()
+                        .map(|(id, frame)| (id.clone(), frame.width, frame.height, frame.data.clone()))
+                        .collect()
+                })
+                .unwrap_or_default();
+
+            {
+                if tile_frames.is_empty() {
                     ui.label("ビデオフレームを待機中...");
+                } else {
+                    // 表示されなくなったproducerのテクスチャは溜め込まない
+                    let live_ids: std::collections::HashSet<&ProducerId> =
+                        tile_frames.iter().map(|(id, ..)| id).collect();
+                    self.video_textures.retain(|id, _| live_ids.contains(id));
+
+                    let tile_count = tile_frames.len();
+                    let columns = (tile_count as f64).sqrt().ceil() as usize;
+
+                    egui::Grid::new("producer_tile_grid")
+                        .num_columns(columns.max(1))
+                        .spacing([8.0, 8.0])
+                        .show(ui, |ui| {
+                            for (i, (producer_id, width, height, data)) in tile_frames.iter().enumerate() {
+                                let color_image =
+                                    egui::ColorImage::from_rgba_unmultiplied([*width, *height], data);
+
+                                let texture = self.video_textures.entry(producer_id.clone()).or_insert_with(|| {
+                                    ctx.load_texture(
+                                        format!("video-frame-{}", producer_id),
+                                        color_image.clone(),
+                                        egui::TextureOptions::LINEAR,
+                                    )
+                                });
+                                texture.set(color_image, egui::TextureOptions::LINEAR);
+
+                                ui.vertical(|ui| {
+                                    ui.label(format!("🧑‍💻 {}", producer_id));
+                                    let response = ui.add(
+                                        egui::Image::new(&*texture)
+                                            .sense(egui::Sense::click_and_drag()),
+                                    );
+
+                                    if response.hovered() {
+                                        self.focused_producer = Some(producer_id.clone());
+                                    }
+
+                                    // widgetローカル座標 -> 0..width/0..height のフレーム座標に変換
+                                    if let Some(pos) = response.hover_pos() {
+                                        let rel = pos - response.rect.min;
+                                        let nx = (rel.x / response.rect.width()).clamp(0.0, 1.0) as f64
+                                            * *width as f64;
+                                        let ny = (rel.y / response.rect.height()).clamp(0.0, 1.0) as f64
+                                            * *height as f64;
+
+                                        // ホバー中は毎repaint(約60fps)呼ばれるため、前回送った座標から
+                                        // 実際に動いた時だけMouseMoveを送ってデータチャンネルのスパムを防ぐ
+                                        let moved = self
+                                            .last_sent_pointer_pos
+                                            .get(producer_id)
+                                            .map(|&(px, py)| px != nx || py != ny)
+                                            .unwrap_or(true);
+                                        if moved {
+                                            self.last_sent_pointer_pos.insert(producer_id.clone(), (nx, ny));
+                                            self.send_navigation_event(
+                                                producer_id,
+                                                format!(
+                                                    "{{\"event\":\"MouseMove\",\"x\":{},\"y\":{}}}",
+                                                    nx, ny
+                                                ),
+                                            );
+                                        }
+
+                                        // GstNavigation/X11の慣習に合わせたボタン番号(1始まり:
+                                        // 左=1, 中央=2, 右=3)。webのMouseEvent.button(0始まり)
+                                        // とは異なるので混同しないこと。
+                                        for (button, gst_button) in [
+                                            (egui::PointerButton::Primary, 1u32),
+                                            (egui::PointerButton::Middle, 2u32),
+                                            (egui::PointerButton::Secondary, 3u32),
+                                        ] {
+                                            if ui.input(|i| i.pointer.button_pressed(button)) {
+                                                self.send_navigation_event(
+                                                    producer_id,
+                                                    format!(
+                                                        "{{\"event\":\"MouseButtonPress\",\"button\":{},\"x\":{},\"y\":{}}}",
+                                                        gst_button, nx, ny
+                                                    ),
+                                                );
+                                            }
+                                            if ui.input(|i| i.pointer.button_released(button)) {
+                                                self.send_navigation_event(
+                                                    producer_id,
+                                                    format!(
+                                                        "{{\"event\":\"MouseButtonRelease\",\"button\":{},\"x\":{},\"y\":{}}}",
+                                                        gst_button, nx, ny
+                                                    ),
+                                                );
+                                            }
+                                        }
+
+                                        let scroll = ui.input(|i| i.smooth_scroll_delta);
+                                        if scroll != egui::Vec2::ZERO {
+                                            self.send_navigation_event(
+                                                producer_id,
+                                                format!(
+                                                    "{{\"event\":\"MouseScroll\",\"dx\":{},\"dy\":{},\"x\":{},\"y\":{}}}",
+                                                    scroll.x, scroll.y, nx, ny
+                                                ),
+                                            );
+                                        }
+                                    }
+
+                                    // 回線品質オーバーレイ(gst-meetのColibriレポートに相当)
+                                    if let Ok(stats) = self.stats.lock() {
+                                        if let Some(producer_stats) = stats.get(producer_id) {
+                                            ui.horizontal(|ui| {
+                                                ui.label(format!(
+                                                    "📶 {:.0} kbps  ⚠️ {:.1}%  🛰️ {:.0} ms  🫨 {:.1} ms",
+                                                    producer_stats.bitrate_bps / 1000.0,
+                                                    producer_stats.packet_loss_pct,
+                                                    producer_stats.rtt_ms,
+                                                    producer_stats.jitter_ms,
+                                                ));
+                                            });
+                                            draw_bitrate_sparkline(
+                                                ui,
+                                                &producer_stats.bitrate_history,
+                                                egui::vec2(response.rect.width(), 24.0),
+                                            );
+                                        }
+                                    }
+
+                                    // 視聴しながらの録画トグル
+                                    let is_recording = self
+                                        .recordings
+                                        .lock()
+                                        .map(|recordings| recordings.contains_key(producer_id))
+                                        .unwrap_or(false);
+
+                                    if ui
+                                        .button(if is_recording { "⏹️ 録画停止" } else { "⏺️ 録画" })
+                                        .clicked()
+                                    {
+                                        if is_recording {
+                                            self.stop_recording(producer_id);
+                                        } else if let Err(e) = self.start_recording(producer_id) {
+                                            self.add_log(format!("❌ 録画開始エラー: {}", e));
+                                        }
+                                    }
+                                });
+
+                                if (i + 1) % columns.max(1) == 0 {
+                                    ui.end_row();
+                                }
+                            }
+                        });
+                }
+            }
+
+            // フォーカス中のタイルへキー入力をNavigationEventとして転送(押下と離上の両方)
+            if let Some(producer_id) = self.focused_producer.clone() {
+                let key_events: Vec<(String, bool)> = ctx.input(|i| {
+                    i.events
+                        .iter()
+                        .filter_map(|event| match event {
+                            egui::Event::Key { key, pressed, .. } => {
+                                gst_navigation_key_name(*key).map(|name| (name, *pressed))
+                            }
+                            _ => None,
+                        })
+                        .collect()
+                });
+
+                for (key_name, pressed) in key_events {
+                    let event_name = if pressed { "KeyPress" } else { "KeyRelease" };
+                    self.send_navigation_event(
+                        &producer_id,
+                        format!("{{\"event\":\"{}\",\"key\":\"{}\"}}", event_name, key_name),
+                    );
                 }
             }
 
@@ -329,6 +1555,33 @@ impl eframe::App for WebRtcApp {
     fn on_exit(&mut self, _gl: Option<&eframe::glow::Context>) {
         self.stop_pipeline();
     }
+
+    fn save(&mut self, storage: &mut dyn eframe::Storage) {
+        eframe::set_value(storage, SETTINGS_STORAGE_KEY, &self.settings);
+    }
+}
+
+/// 受信ビットレートの推移を細い折れ線で描く簡易スパークライン
+fn draw_bitrate_sparkline(ui: &mut egui::Ui, history: &VecDeque<f64>, size: egui::Vec2) {
+    let (rect, _response) = ui.allocate_exact_size(size, egui::Sense::hover());
+
+    if history.len() < 2 {
+        return;
+    }
+
+    let max_bps = history.iter().cloned().fold(0.0_f64, f64::max).max(1.0);
+    let points: Vec<egui::Pos2> = history
+        .iter()
+        .enumerate()
+        .map(|(i, &bps)| {
+            let x = rect.left() + rect.width() * (i as f32 / (history.len() - 1) as f32);
+            let y = rect.bottom() - rect.height() * (bps / max_bps) as f32;
+            egui::pos2(x, y)
+        })
+        .collect();
+
+    ui.painter()
+        .add(egui::Shape::line(points, egui::Stroke::new(1.5, egui::Color32::LIGHT_GREEN)));
 }
 
 fn main() -> Result<(), eframe::Error> {
@@ -367,7 +1620,7 @@ fn main() -> Result<(), eframe::Error> {
             // フォント設定を適用
             cc.egui_ctx.set_fonts(fonts);
 
-            Ok(Box::new(WebRtcApp::default()))
+            Ok(Box::new(WebRtcApp::new(cc)))
         }),
     )
 }